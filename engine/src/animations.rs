@@ -1,5 +1,5 @@
 use crate::sprite::{Action, Character};
-use crate::types::{Image, Rect, Vec2i};
+use crate::types::{composite_and_pack_frames, premultiply_rgba, CanvasDispose, Image, Rect, Vec2i};
 use std::collections::hash_map::HashMap;
 use std::rc::Rc;
 
@@ -26,7 +26,10 @@ impl Animation {
 
     pub fn current_frame(&self, start_time: usize, now: usize, speedup_factor: &usize) -> Rect {
         let frame_timing = (now - start_time) / speedup_factor;
-        self.frames[frame_timing]
+        // A non-looping animation that's already finished keeps reporting its
+        // last frame instead of indexing past the end, so a queue can tick it
+        // once more before `is_finished` gets a chance to drop it.
+        self.frames[frame_timing.min(self.frames.len() - 1)]
     }
 
     #[allow(dead_code)]
@@ -90,6 +93,58 @@ impl AnimationSet {
         &self.image
     }
 
+    /// Decodes a GIF into a single-`Animation` `AnimationSet` for `action`.
+    pub fn from_gif(path: &std::path::Path, character: Character, action: Action) -> Self {
+        use std::fs::File;
+        const TICKS_PER_SECOND: f32 = 60.0;
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(File::open(path).unwrap()).unwrap();
+
+        let sz = Vec2i {
+            x: decoder.width() as i32,
+            y: decoder.height() as i32,
+        };
+
+        let mut decoded = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            let frame_sz = Vec2i {
+                x: frame.width as i32,
+                y: frame.height as i32,
+            };
+            let frame_image = Image {
+                buffer: premultiply_rgba(&frame.buffer),
+                sz: frame_sz,
+            };
+
+            let to = Vec2i {
+                x: frame.left as i32,
+                y: frame.top as i32,
+            };
+            let dispose = match frame.dispose {
+                gif::DisposalMethod::Any | gif::DisposalMethod::Keep => CanvasDispose::None,
+                gif::DisposalMethod::Background => CanvasDispose::Background,
+                gif::DisposalMethod::Previous => CanvasDispose::Previous,
+            };
+            // `frame.delay` is in hundredths of a second.
+            let ticks = (frame.delay as f32 / 100.0 * TICKS_PER_SECOND).round() as usize;
+
+            decoded.push((frame_image, to, false, dispose, ticks));
+        }
+
+        let (image, animation) = composite_and_pack_frames(sz, decoded);
+
+        let mut animations = HashMap::new();
+        animations.insert(action, Rc::new(animation));
+
+        Self {
+            character,
+            image,
+            animations,
+        }
+    }
+
     // pub fn get_animation_state(&self, action: Action) -> AnimationState {
     //     AnimationState {
     //         start_time: 0,
@@ -99,62 +154,81 @@ impl AnimationSet {
     // }
 }
 
-// struct AnimQueue {
-//     queue: Vec<(f32, AnimationState, bool)>,
-// }
-
-// impl AnimQueue {
-//     #[allow(dead_code)]
-//     fn push(&mut self, p: f32, anim: AnimationState, pause: bool, retrigger: bool) {
-//         // If this is a retrigger, replace the old animation (if any)
-//         // otherwise, leave the old animation alone!
-//         let old_anim = anim.clone();
-//         let to_insert = if let Some(found_pos) = self
-//             .queue
-//             .iter()
-//             .position(|(qp, qanim, _)| qanim.animation == anim.animation)
-//         {
-//             let (_qp, qanim, _qpause) = self.queue.remove(found_pos);
-//             if retrigger {
-//                 (p, anim, pause)
-//             } else {
-//                 (p, qanim, pause)
-//             }
-//         } else {
-//             (p, anim, pause)
-//         };
-//         // put highest priority thing at end
-//         let pos = self
-//             .queue
-//             .iter()
-//             .rposition(|(qp, _, _)| qp < &p)
-//             .unwrap_or(0);
-//         self.queue.insert(pos, (p, old_anim, pause));
-//     }
-
-//     #[allow(dead_code)]
-//     fn tick(&mut self) {
-//         let qlen = self.queue.len();
-//         // tick possibly-paused non-current animations
-//         if qlen > 1 {
-//             for (_p, anim, pause) in self.queue.iter_mut().take(qlen - 2) {
-//                 if !(*pause) {
-//                     anim.tick();
-//                 }
-//             }
-//         }
-//         if let Some((_, active, _)) = self.queue.last() {
-//             active.tick();
-//         }
-//         // Throw away finished animations
-//         self.queue.retain(|(_p, anim, _)| !anim.is_finished());
-//     }
-
-//     // Got to return option here --- nothing to return if no animations in the queue!
-//     #[allow(dead_code)]
-//     fn current_frame(&self) -> Option<Rect> {
-//         self.queue
-//             .last()
-//             .map(|(_, anim, _)| anim.animation.current_frame(0, 0, 0))
-//     }
-// }
\ No newline at end of file
+/// A priority queue of in-flight animations. The last entry (highest
+/// priority) is the one that's rendered.
+pub struct AnimQueue {
+    queue: Vec<(f32, AnimationState, bool)>,
+    active_frame: Option<Rect>,
+}
+
+impl Default for AnimQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnimQueue {
+    pub fn new() -> Self {
+        Self {
+            queue: Vec::new(),
+            active_frame: None,
+        }
+    }
+
+    /// Inserts `anim` ordered by `priority` (highest priority ends up last,
+    /// i.e. active). If an entry for the same underlying `Animation` already
+    /// exists, `retrigger` decides whether it's restarted (`true`) or left to
+    /// keep playing (`false`); either way `pause_others` is updated.
+    pub fn push(&mut self, priority: f32, anim: AnimationState, pause_others: bool, retrigger: bool) {
+        let to_insert = if let Some(found_pos) = self
+            .queue
+            .iter()
+            .position(|(_, qanim, _)| qanim.animation == anim.animation)
+        {
+            let (_, qanim, _) = self.queue.remove(found_pos);
+            if retrigger {
+                anim
+            } else {
+                qanim
+            }
+        } else {
+            anim
+        };
+        let pos = self
+            .queue
+            .iter()
+            .rposition(|(qp, _, _)| *qp <= priority)
+            .map(|p| p + 1)
+            .unwrap_or(0);
+        self.queue.insert(pos, (priority, to_insert, pause_others));
+    }
+
+    /// Advances the active (highest-priority) animation and, unless the active
+    /// entry was pushed with `pause_others`, every lower-priority one, then
+    /// drops any that finished and don't loop.
+    pub fn tick(&mut self, speedup_factor: &usize) {
+        let qlen = self.queue.len();
+        let pause_others = self.queue.last().map_or(false, |(_, _, p)| *p);
+        if qlen > 1 && !pause_others {
+            for (_, anim, _) in self.queue.iter_mut().take(qlen - 1) {
+                anim.tick(speedup_factor);
+            }
+        }
+        self.active_frame = self
+            .queue
+            .last_mut()
+            .map(|(_, active, _)| active.tick(speedup_factor));
+
+        self.queue.retain(|(_, anim, _)| {
+            anim.animation.loops
+                || !anim
+                    .animation
+                    .is_finished(anim.start_time, anim.now, speedup_factor)
+        });
+    }
+
+    /// The active animation's current frame, or `None` if the queue is empty.
+    pub fn current_frame(&self) -> Option<Rect> {
+        self.active_frame
+    }
+}
\ No newline at end of file
@@ -1,3 +1,7 @@
+use crate::animations::Animation;
+use std::collections::HashMap;
+use std::hash::Hash;
+
 pub type Color = (u8, u8, u8, u8);
 
 #[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
@@ -36,6 +40,55 @@ impl Rect {
     }
 }
 
+/// A 2x3 affine transform (rotation + non-uniform scale + translation),
+/// stored as two rows `[a, b, tx]` / `[c, d, ty]` so that
+/// `x' = a*x + b*y + tx` and `y' = c*x + d*y + ty`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Affine2 {
+    pub m: [[f32; 3]; 2],
+}
+
+impl Affine2 {
+    pub fn identity() -> Self {
+        Self {
+            m: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        }
+    }
+
+    pub fn from_rotation_scale(rotation: f32, scale_x: f32, scale_y: f32) -> Self {
+        let (s, c) = rotation.sin_cos();
+        Self {
+            m: [
+                [c * scale_x, -s * scale_y, 0.0],
+                [s * scale_x, c * scale_y, 0.0],
+            ],
+        }
+    }
+
+    pub fn apply(&self, p: (f32, f32)) -> (f32, f32) {
+        let [row_x, row_y] = self.m;
+        (
+            row_x[0] * p.0 + row_x[1] * p.1 + row_x[2],
+            row_y[0] * p.0 + row_y[1] * p.1 + row_y[2],
+        )
+    }
+
+    pub fn invert(&self) -> Self {
+        let [[a, b, tx], [c, d, ty]] = self.m;
+        let inv_det = 1.0 / (a * d - b * c);
+        let ia = d * inv_det;
+        let ib = -b * inv_det;
+        let ic = -c * inv_det;
+        let id = a * inv_det;
+        Self {
+            m: [
+                [ia, ib, -(ia * tx + ib * ty)],
+                [ic, id, -(ic * tx + id * ty)],
+            ],
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Hash, Debug)]
 pub struct Image {
     pub buffer: Box<[Color]>,
@@ -56,22 +109,13 @@ impl Image {
         use std::fs::File;
         let decoder = png::Decoder::new(File::open(p).unwrap());
         let mut reader = decoder.read_info().unwrap();
-        
+
         let mut buf = vec![0; reader.output_buffer_size()];
-        
+
         let info = reader.next_frame(&mut buf).unwrap();
         assert_eq!(info.color_type, png::ColorType::Rgba);
         Self {
-            buffer: buf
-                .chunks_exact(4)
-                .map(|px| {
-                    let a = px[3] as f32 / 255.0;
-                    let r = (px[0] as f32 * a) as u8;
-                    let g = (px[1] as f32 * a) as u8;
-                    let b = (px[2] as f32 * a) as u8;
-                    (r, g, b, a as u8) // Color
-                })
-                .collect::<Box<[Color]>>(),
+            buffer: premultiply_rgba(&buf),
             sz: Vec2i {
                 x: info.width as i32,
                 y: info.height as i32,
@@ -79,6 +123,78 @@ impl Image {
         }
     }
 
+    /// Decodes an APNG (animated PNG) into a sprite-sheet `Image` holding every
+    /// composited frame side-by-side, plus the `Animation` describing where each
+    /// frame lives in that sheet and how long it's shown.
+    pub fn animation_from_file(p: &std::path::Path) -> (Self, Animation) {
+        use std::fs::File;
+        const TICKS_PER_SECOND: f32 = 60.0;
+
+        let decoder = png::Decoder::new(File::open(p).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+
+        let sz = Vec2i {
+            x: reader.info().width as i32,
+            y: reader.info().height as i32,
+        };
+        let num_frames = reader
+            .info()
+            .animation_control
+            .map(|ac| ac.num_frames as usize)
+            .unwrap_or(1);
+
+        let mut decoded = Vec::with_capacity(num_frames);
+        for _ in 0..num_frames {
+            let mut buf = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut buf).unwrap();
+            assert_eq!(info.color_type, png::ColorType::Rgba);
+
+            let frame = Self {
+                buffer: premultiply_rgba(&buf[..info.buffer_size()]),
+                sz: Vec2i {
+                    x: info.width as i32,
+                    y: info.height as i32,
+                },
+            };
+
+            // `frame_control` is only `Some` when the frame came from an
+            // `fcTL` chunk; a plain, non-animated PNG has no `acTL`/`fcTL` at
+            // all, so treat it as a single full-image frame instead.
+            let (to, force_replace, dispose, ticks) = match reader.info().frame_control {
+                Some(fc) => {
+                    let to = Vec2i {
+                        x: fc.x_offset as i32,
+                        y: fc.y_offset as i32,
+                    };
+                    // BlendOp::Source overwrites the frame region outright
+                    // instead of alpha-compositing; composite_and_pack_frames
+                    // does that by clearing the region to transparent before
+                    // blitting onto it.
+                    let force_replace = fc.blend_op == png::BlendOp::Source;
+                    let dispose = match fc.dispose_op {
+                        png::DisposeOp::None => CanvasDispose::None,
+                        png::DisposeOp::Background => CanvasDispose::Background,
+                        png::DisposeOp::Previous => CanvasDispose::Previous,
+                    };
+                    let delay_den = if fc.delay_den == 0 { 100 } else { fc.delay_den };
+                    let ticks =
+                        (fc.delay_num as f32 / delay_den as f32 * TICKS_PER_SECOND).round() as usize;
+                    (to, force_replace, dispose, ticks)
+                }
+                None => (
+                    Vec2i { x: 0, y: 0 },
+                    true,
+                    CanvasDispose::None,
+                    TICKS_PER_SECOND as usize,
+                ),
+            };
+
+            decoded.push((frame, to, force_replace, dispose, ticks));
+        }
+
+        composite_and_pack_frames(sz, decoded)
+    }
+
     pub fn size(&self) -> (i32, i32) {
         (self.sz.x, self.sz.y)
     }
@@ -103,7 +219,16 @@ impl Image {
         self.buffer[y * self.sz.x as usize + x0..(y * self.sz.x as usize + x1)].fill(c);
     }
 
-    pub fn bitblt(&mut self, src: &Image, from: Rect, to: Vec2i) {
+    /// Clips `from`/`to` against both images, then calls `blend(from_pixel,
+    /// to_pixel)` once per overlapping pixel pair. Shared by `bitblt` and
+    /// `bitblt_transformed`, which differ only in the per-pixel blend.
+    fn blit_clipped(
+        &mut self,
+        src: &Image,
+        from: Rect,
+        to: Vec2i,
+        mut blend: impl FnMut(Color, &mut Color),
+    ) {
         assert!(Rect {
             pos: Vec2i { x: 0, y: 0 },
             sz: src.sz
@@ -120,7 +245,7 @@ impl Image {
         let x_skip = to_x.max(0) - to_x;
         let y_count = (to_y + from.sz.y as i32).min(self.sz.y) - to_y;
         let x_count = (to_x + from.sz.x as i32).min(self.sz.x) - to_x;
-        
+
         debug_assert!(0 <= x_skip);
         debug_assert!(0 <= y_skip);
         debug_assert!(0 <= x_count);
@@ -133,12 +258,12 @@ impl Image {
         debug_assert!(0 <= from.pos.y + y_skip);
         debug_assert!(to_x + x_count <= self.sz.x);
         debug_assert!(to_y + y_count <= self.sz.y);
-        
+
         let from_start: usize = src_pitch * (from.pos.y + y_skip) as usize;
         let from_stop: usize = src_pitch * (from.pos.y + y_count) as usize;
         let to_start: usize = dst_pitch * (to_y + y_skip) as usize;
         let to_stop: usize = dst_pitch * (to_y + y_count) as usize;
-        
+
         for (row_a, row_b) in src.buffer[from_start..from_stop]
             .chunks_exact(src_pitch)
             .zip(self.buffer[to_start..to_stop].chunks_exact_mut(dst_pitch))
@@ -149,21 +274,501 @@ impl Image {
             let from_row_start = (from.pos.x + x_skip) as usize;
             let from_row_stop = (from.pos.x + x_count) as usize;
             let from_cols = row_a[from_row_start..from_row_stop].iter();
-            
+
             for (to, from) in to_cols.zip(from_cols) {
+                blend(*from, to);
+            }
+        }
+    }
+
+    pub fn bitblt(&mut self, src: &Image, from: Rect, to: Vec2i) {
+        self.blit_clipped(src, from, to, |from, to| {
+            let ta = to.3 as f32 / 255.0;
+            let fa = from.3 as f32 / 255.0;
+            to.0 = from
+                .0
+                .saturating_add((to.0 as f32 * (1.0 - fa)).round() as u8);
+            to.1 = from
+                .1
+                .saturating_add((to.1 as f32 * (1.0 - fa)).round() as u8);
+            to.2 = from
+                .2
+                .saturating_add((to.2 as f32 * (1.0 - fa)).round() as u8);
+            to.3 = ((fa + ta * (1.0 - fa)) * 255.0).round() as u8;
+        });
+    }
+
+    /// Same as `bitblt`, but runs each source pixel through `xform` before
+    /// alpha-compositing it.
+    pub fn bitblt_transformed(&mut self, src: &Image, from: Rect, to: Vec2i, xform: ColorTransform) {
+        self.blit_clipped(src, from, to, |from, to| {
+            let from = xform.apply(from);
+
+            let ta = to.3 as f32 / 255.0;
+            let fa = from.3 as f32 / 255.0;
+            to.0 = from
+                .0
+                .saturating_add((to.0 as f32 * (1.0 - fa)).round() as u8);
+            to.1 = from
+                .1
+                .saturating_add((to.1 as f32 * (1.0 - fa)).round() as u8);
+            to.2 = from
+                .2
+                .saturating_add((to.2 as f32 * (1.0 - fa)).round() as u8);
+            to.3 = ((fa + ta * (1.0 - fa)) * 255.0).round() as u8;
+        });
+    }
+
+    /// Blits `from` through `transform` (rotation/scale) and places it at
+    /// `origin`, sampling with nearest-neighbor and alpha-compositing exactly
+    /// like `bitblt`.
+    pub fn bitblt_affine(&mut self, src: &Image, from: Rect, transform: Affine2, origin: Vec2i) {
+        assert!(Rect {
+            pos: Vec2i { x: 0, y: 0 },
+            sz: src.sz
+        }
+        .contains(from));
+
+        let corners = [
+            (0.0, 0.0),
+            (from.sz.x as f32, 0.0),
+            (0.0, from.sz.y as f32),
+            (from.sz.x as f32, from.sz.y as f32),
+        ]
+        .map(|p| transform.apply(p));
+
+        let min_x = corners.iter().map(|p| p.0).fold(f32::INFINITY, f32::min).floor() as i32;
+        let max_x = corners
+            .iter()
+            .map(|p| p.0)
+            .fold(f32::NEG_INFINITY, f32::max)
+            .ceil() as i32;
+        let min_y = corners.iter().map(|p| p.1).fold(f32::INFINITY, f32::min).floor() as i32;
+        let max_y = corners
+            .iter()
+            .map(|p| p.1)
+            .fold(f32::NEG_INFINITY, f32::max)
+            .ceil() as i32;
+
+        let inverse = transform.invert();
+
+        for dy in min_y..max_y {
+            let wy = origin.y + dy;
+            if wy < 0 || wy >= self.sz.y {
+                continue;
+            }
+            for dx in min_x..max_x {
+                let wx = origin.x + dx;
+                if wx < 0 || wx >= self.sz.x {
+                    continue;
+                }
+
+                // Sample the destination pixel's center, not its corner, so
+                // axis-aligned rotations (90/180/270) don't drop a row/column
+                // of source pixels at the source-space boundary.
+                let (sx, sy) = inverse.apply((dx as f32 + 0.5, dy as f32 + 0.5));
+                if sx < 0.0 || sy < 0.0 || sx >= from.sz.x as f32 || sy >= from.sz.y as f32 {
+                    continue;
+                }
+
+                let src_x = from.pos.x + sx as i32;
+                let src_y = from.pos.y + sy as i32;
+                let from_px = src.buffer[(src_y * src.sz.x + src_x) as usize];
+                let to = &mut self.buffer[(wy * self.sz.x + wx) as usize];
+
                 let ta = to.3 as f32 / 255.0;
-                let fa = from.3 as f32 / 255.0;
-                to.0 = from
+                let fa = from_px.3 as f32 / 255.0;
+                to.0 = from_px
                     .0
                     .saturating_add((to.0 as f32 * (1.0 - fa)).round() as u8);
-                to.1 = from
+                to.1 = from_px
                     .1
                     .saturating_add((to.1 as f32 * (1.0 - fa)).round() as u8);
-                to.2 = from
+                to.2 = from_px
                     .2
                     .saturating_add((to.2 as f32 * (1.0 - fa)).round() as u8);
                 to.3 = ((fa + ta * (1.0 - fa)) * 255.0).round() as u8;
             }
         }
     }
+
+    /// Writes recorded `frames` out as an animated GIF at `path`, one delay
+    /// (in 1/100s units) per frame. GIF is palette-based, so a single
+    /// <=256-color palette is built across every frame with median-cut
+    /// quantization and every pixel is mapped to its nearest entry; fully
+    /// transparent pixels map to a reserved transparent index instead.
+    pub fn write_gif(path: &std::path::Path, frames: &[Image], delays: &[u16]) {
+        assert_eq!(frames.len(), delays.len());
+        let sz = frames[0].sz;
+
+        let samples: Vec<(u8, u8, u8)> = frames
+            .iter()
+            .flat_map(|frame| frame.buffer.iter())
+            .filter(|&&(_, _, _, a)| a > 0)
+            .map(|&(r, g, b, _)| (r, g, b))
+            .collect();
+
+        // Reserve one palette slot for transparency.
+        let palette = median_cut_palette(samples, 255);
+        let transparent_index = palette.len() as u8;
+
+        let mut global_palette = Vec::with_capacity((palette.len() + 1) * 3);
+        for &(r, g, b) in &palette {
+            global_palette.extend_from_slice(&[r, g, b]);
+        }
+        global_palette.extend_from_slice(&[0, 0, 0]);
+
+        let file = std::fs::File::create(path).unwrap();
+        let mut encoder =
+            gif::Encoder::new(file, sz.x as u16, sz.y as u16, &global_palette).unwrap();
+        encoder.set_repeat(gif::Repeat::Infinite).unwrap();
+
+        for (image, &delay) in frames.iter().zip(delays) {
+            let indices: Vec<u8> = image
+                .buffer
+                .iter()
+                .map(|&(r, g, b, a)| {
+                    if a == 0 {
+                        transparent_index
+                    } else {
+                        nearest_palette_index(&palette, (r, g, b))
+                    }
+                })
+                .collect();
+
+            let mut frame = gif::Frame::from_indexed_pixels(sz.x as u16, sz.y as u16, indices, None);
+            frame.delay = delay;
+            frame.transparent = Some(transparent_index);
+            frame.dispose = gif::DisposalMethod::Background;
+            encoder.write_frame(&frame).unwrap();
+        }
+    }
+}
+
+/// Decodes a run of raw RGBA bytes into premultiplied-alpha `Color`s, keeping
+/// the original 0-255 alpha byte (not the 0.0..1.0 fraction used to do the
+/// premultiply) so downstream compositing sees real alpha values.
+pub(crate) fn premultiply_rgba(buf: &[u8]) -> Box<[Color]> {
+    buf.chunks_exact(4)
+        .map(|px| {
+            let a = px[3] as f32 / 255.0;
+            let r = (px[0] as f32 * a) as u8;
+            let g = (px[1] as f32 * a) as u8;
+            let b = (px[2] as f32 * a) as u8;
+            (r, g, b, px[3]) // Color
+        })
+        .collect()
+}
+
+/// How a composited animation frame affects the running canvas once it's
+/// been shown, shared between the APNG and GIF importers.
+pub(crate) enum CanvasDispose {
+    None,
+    Background,
+    Previous,
+}
+
+/// Composites decoded `(frame, placement, force_replace, dispose, ticks)`
+/// entries onto a running `sz`-sized transparent canvas — `force_replace`
+/// clears the frame's region to transparent before blitting it, for importers
+/// like APNG's `BlendOp::Source` that overwrite rather than alpha-composite —
+/// then packs every composited canvas side-by-side into one sprite sheet
+/// `Image` plus the matching `Animation`. Shared by `Image::animation_from_file`
+/// and `AnimationSet::from_gif`.
+pub(crate) fn composite_and_pack_frames(
+    sz: Vec2i,
+    decoded_frames: Vec<(Image, Vec2i, bool, CanvasDispose, usize)>,
+) -> (Image, Animation) {
+    let mut canvas = Image::new(sz);
+    canvas.clear((0, 0, 0, 0));
+
+    let mut composited = Vec::with_capacity(decoded_frames.len());
+    let mut frame_timings = Vec::with_capacity(decoded_frames.len());
+
+    for (frame, to, force_replace, dispose, ticks) in decoded_frames {
+        let pre_frame_canvas = canvas.clone();
+
+        if force_replace {
+            canvas.draw_rect(&Rect { pos: to, sz: frame.sz }, (0, 0, 0, 0));
+        }
+        let from = Rect {
+            pos: Vec2i { x: 0, y: 0 },
+            sz: frame.sz,
+        };
+        canvas.bitblt(&frame, from, to);
+
+        composited.push(canvas.clone());
+
+        match dispose {
+            CanvasDispose::None => {}
+            CanvasDispose::Background => {
+                canvas.draw_rect(&Rect { pos: to, sz: frame.sz }, (0, 0, 0, 0));
+            }
+            CanvasDispose::Previous => {
+                canvas = pre_frame_canvas;
+            }
+        }
+
+        frame_timings.push(ticks.max(1));
+    }
+
+    // Pack the composited frames side-by-side into one sprite sheet.
+    let mut sheet = Image::new(Vec2i {
+        x: sz.x * composited.len().max(1) as i32,
+        y: sz.y,
+    });
+    sheet.clear((0, 0, 0, 0));
+    let mut frames = Vec::with_capacity(composited.len());
+    for (i, frame) in composited.iter().enumerate() {
+        let to = Vec2i { x: sz.x * i as i32, y: 0 };
+        sheet.bitblt(
+            frame,
+            Rect {
+                pos: Vec2i { x: 0, y: 0 },
+                sz,
+            },
+            to,
+        );
+        frames.push(Rect { pos: to, sz });
+    }
+
+    (
+        sheet,
+        Animation {
+            frames,
+            frame_timings,
+            loops: true,
+        },
+    )
+}
+
+/// Finds the channel (0=R, 1=G, 2=B) with the widest range across `pixels`
+/// and reports that channel and its range; on a tie the later channel wins.
+fn widest_channel(pixels: &[(u8, u8, u8)]) -> (usize, u8) {
+    let mut mins = [u8::MAX; 3];
+    let mut maxs = [0u8; 3];
+    for &(r, g, b) in pixels {
+        for (i, c) in [r, g, b].into_iter().enumerate() {
+            mins[i] = mins[i].min(c);
+            maxs[i] = maxs[i].max(c);
+        }
+    }
+    (0..3).map(|i| (i, maxs[i] - mins[i])).max_by_key(|&(_, range)| range).unwrap()
 }
+
+/// Builds a palette of at most `max_colors` colors via median-cut: the box
+/// with the largest single-channel range is repeatedly split at the median
+/// along that channel until there are enough boxes, then each box's average
+/// color becomes its palette entry.
+fn median_cut_palette(pixels: Vec<(u8, u8, u8)>, max_colors: usize) -> Vec<(u8, u8, u8)> {
+    if pixels.is_empty() {
+        return vec![(0, 0, 0)];
+    }
+
+    let mut boxes = vec![pixels];
+    while boxes.len() < max_colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .max_by_key(|(_, b)| widest_channel(b).1)
+            .map(|(i, _)| i);
+        let Some(idx) = widest else { break };
+
+        let mut splitting = boxes.swap_remove(idx);
+        let (channel, _) = widest_channel(&splitting);
+        splitting.sort_by_key(|&(r, g, b)| [r, g, b][channel]);
+        let upper_half = splitting.split_off(splitting.len() / 2);
+        boxes.push(splitting);
+        boxes.push(upper_half);
+    }
+
+    boxes
+        .into_iter()
+        .map(|b| {
+            let n = b.len() as u32;
+            let (r, g, b) = b.iter().fold((0u32, 0u32, 0u32), |(ar, ag, ab), &(r, g, b)| {
+                (ar + r as u32, ag + g as u32, ab + b as u32)
+            });
+            ((r / n) as u8, (g / n) as u8, (b / n) as u8)
+        })
+        .collect()
+}
+
+/// Finds the palette entry closest to `c` by squared R/G/B distance.
+fn nearest_palette_index(palette: &[(u8, u8, u8)], c: (u8, u8, u8)) -> u8 {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|&(_, &(pr, pg, pb))| {
+            let dr = pr as i32 - c.0 as i32;
+            let dg = pg as i32 - c.1 as i32;
+            let db = pb as i32 - c.2 as i32;
+            dr * dr + dg * dg + db * db
+        })
+        .map(|(i, _)| i as u8)
+        .unwrap()
+}
+
+/// A Flash-style color transform: each channel is scaled by a multiplier and
+/// then shifted by an additive term before compositing, e.g.
+/// `r_add = g_add = b_add = 255` for a white hit-flash or `g_mult = b_mult = 0` for a red damage tint.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ColorTransform {
+    pub r_mult: f32,
+    pub g_mult: f32,
+    pub b_mult: f32,
+    pub a_mult: f32,
+    pub r_add: f32,
+    pub g_add: f32,
+    pub b_add: f32,
+    pub a_add: f32,
+}
+
+impl ColorTransform {
+    pub const IDENTITY: ColorTransform = ColorTransform {
+        r_mult: 1.0,
+        g_mult: 1.0,
+        b_mult: 1.0,
+        a_mult: 1.0,
+        r_add: 0.0,
+        g_add: 0.0,
+        b_add: 0.0,
+        a_add: 0.0,
+    };
+
+    fn apply(&self, c: Color) -> Color {
+        let chan = |v: f32, mult: f32, add: f32| (v * mult + add).clamp(0.0, 255.0);
+
+        // `c`'s R/G/B are premultiplied by its old alpha; un-premultiply them
+        // to true color before the mult/add, then re-premultiply by the new
+        // alpha so the result still satisfies bitblt's premultiplied-over math.
+        let old_a = c.3 as f32 / 255.0;
+        let (r, g, b) = if old_a > 0.0 {
+            (c.0 as f32 / old_a, c.1 as f32 / old_a, c.2 as f32 / old_a)
+        } else {
+            (0.0, 0.0, 0.0)
+        };
+
+        let new_a = chan(c.3 as f32, self.a_mult, self.a_add) / 255.0;
+        (
+            (chan(r, self.r_mult, self.r_add) * new_a) as u8,
+            (chan(g, self.g_mult, self.g_add) * new_a) as u8,
+            (chan(b, self.b_mult, self.b_add) * new_a) as u8,
+            (new_a * 255.0).round() as u8,
+        )
+    }
+}
+
+/// Packs many separately-loaded sprite `Image`s into one sheet `Image`.
+pub struct Atlas;
+
+impl Atlas {
+    /// Packs `sprites` into an `atlas_width`-wide sheet using a shelf
+    /// (next-fit-decreasing-height) packer: sprites are placed tallest-first,
+    /// left-to-right along a shelf until one would overflow the width, at
+    /// which point a new shelf starts below the tallest sprite seen so far
+    /// on the current one. Returns the packed sheet and where each key ended
+    /// up within it.
+    pub fn build<K: Eq + Hash>(mut sprites: Vec<(K, Image)>, atlas_width: i32) -> (Image, HashMap<K, Rect>) {
+        sprites.sort_by_key(|(_, img)| std::cmp::Reverse(img.sz.y));
+
+        let mut positions = Vec::with_capacity(sprites.len());
+        let (mut x, mut y, mut shelf_height, mut atlas_height) = (0, 0, 0, 0);
+        for (_, img) in &sprites {
+            assert!(
+                img.sz.x <= atlas_width,
+                "sprite width {} exceeds atlas_width {}",
+                img.sz.x,
+                atlas_width
+            );
+            if x + img.sz.x > atlas_width {
+                y += shelf_height;
+                x = 0;
+                shelf_height = 0;
+            }
+            positions.push(Vec2i { x, y });
+            x += img.sz.x;
+            shelf_height = shelf_height.max(img.sz.y);
+            atlas_height = atlas_height.max(y + shelf_height);
+        }
+
+        let mut atlas = Image::new(Vec2i {
+            x: atlas_width,
+            y: atlas_height,
+        });
+        atlas.clear((0, 0, 0, 0));
+
+        let mut placements = HashMap::with_capacity(sprites.len());
+        for ((key, img), pos) in sprites.into_iter().zip(positions) {
+            let from = Rect {
+                pos: Vec2i { x: 0, y: 0 },
+                sz: img.sz,
+            };
+            atlas.bitblt(&img, from, pos);
+            placements.insert(key, Rect { pos, sz: img.sz });
+        }
+
+        (atlas, placements)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(sz: Vec2i, c: Color) -> Image {
+        let mut img = Image::new(sz);
+        img.clear(c);
+        img
+    }
+
+    fn pixel(img: &Image, p: Vec2i) -> Color {
+        img.as_slice()[(p.y * img.sz.x + p.x) as usize]
+    }
+
+    #[test]
+    fn dispose_previous_restores_pre_frame_canvas() {
+        let sz = Vec2i { x: 2, y: 2 };
+        let red = solid(sz, (200, 0, 0, 255));
+        let blue = solid(Vec2i { x: 1, y: 1 }, (0, 0, 200, 255));
+        let transparent = solid(Vec2i { x: 1, y: 1 }, (0, 0, 0, 0));
+
+        let decoded = vec![
+            (red, Vec2i { x: 0, y: 0 }, false, CanvasDispose::None, 1),
+            (blue, Vec2i { x: 0, y: 0 }, false, CanvasDispose::Previous, 1),
+            (transparent, Vec2i { x: 0, y: 0 }, false, CanvasDispose::None, 1),
+        ];
+        let (sheet, animation) = composite_and_pack_frames(sz, decoded);
+
+        // Frame 1 shows the blue square blitted over the red canvas.
+        assert_eq!(pixel(&sheet, animation.frames[1].pos), (0, 0, 200, 255));
+        // DisposeOp::Previous restores frame 0's canvas before frame 2 is
+        // composited, so the blue square must not carry over.
+        assert_eq!(pixel(&sheet, animation.frames[2].pos), (200, 0, 0, 255));
+    }
+
+    #[test]
+    fn median_cut_quantizes_to_requested_color_count() {
+        let pixels = vec![
+            (255, 0, 0),
+            (255, 0, 0),
+            (0, 255, 0),
+            (0, 255, 0),
+            (0, 0, 255),
+            (0, 0, 255),
+        ];
+        let palette = median_cut_palette(pixels.clone(), 3);
+        assert_eq!(palette.len(), 3);
+        // The first split is on blue (last of three tied 255 ranges) at the
+        // median index 3, so both reds share a box with one green and average
+        // to (170, 85, 0); the second split separates green from blue.
+        assert_eq!(palette, vec![(170, 85, 0), (0, 255, 0), (0, 0, 255)]);
+
+        for (p, want) in pixels.into_iter().zip([0, 0, 1, 1, 2, 2]) {
+            let idx = nearest_palette_index(&palette, p);
+            assert_eq!(idx, want);
+        }
+    }
+}
+